@@ -0,0 +1,355 @@
+//! Re-derive a cache-friendly, chunked OCI image from a stored artifact.
+//!
+//! The packing strategy mirrors ostree-ext: objects are grouped by an
+//! originating "source" (e.g. the package/component recorded in the
+//! artifact's metadata), sources are sorted largest-first, and each of the
+//! largest sources gets its own dedicated layer until `max_layers - 1` slots
+//! are filled. Everything left over lands in a final "leftovers" layer. When
+//! a prior build's manifest is supplied, sources are pinned to the layer
+//! index they previously occupied so unchanged layers keep identical
+//! digests and stay cache-hot.
+
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::ExportOpts;
+
+/// The manifest [`cli_chunk`] writes back to the repo: the image's objects
+/// repacked into at most `--max-layers` layers, each stored as its own blob.
+///
+/// `sources` is the precise, chunk-specific source grouping (so re-running
+/// `chunk` on its own output reproduces exact layer membership rather than
+/// re-deriving an approximation from merged layers); `layers` is the result
+/// consumed by `--prior-build` pinning and by `inspect`'s derived stats.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ImageManifest {
+    pub(crate) layers: Vec<ManifestLayer>,
+    pub(crate) sources: Vec<SourceRecord>,
+}
+
+/// A source as recorded in an image's metadata: `{id, size, digests}`,
+/// matching what [`parse_sources`] reads back.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SourceRecord {
+    pub(crate) id: String,
+    pub(crate) size: u64,
+    pub(crate) digests: Vec<String>,
+}
+
+/// A single packed layer: the sources it contains (read back by
+/// `--prior-build` pinning), the digest of the blob it was written to, and
+/// the digests of the objects that make it up (read by `inspect`'s derived
+/// stats; no per-object sizes are recorded here).
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ManifestLayer {
+    pub(crate) sources: Vec<String>,
+    pub(crate) digest: String,
+    pub(crate) size: u64,
+    pub(crate) objects: Vec<String>,
+}
+
+/// A group of content-addressed objects that should be packed into the same
+/// layer, e.g. because they originate from the same upstream package.
+#[derive(Debug, Clone)]
+struct ObjectSource {
+    id: String,
+    size: u64,
+    digests: Vec<String>,
+}
+
+/// Layer indices that sources occupied in a prior build, used to keep
+/// unchanged layers bit-for-bit identical across rebuilds.
+#[derive(Debug, Default)]
+struct PriorAssignment {
+    layer_of_source: BTreeMap<String, usize>,
+}
+
+impl PriorAssignment {
+    fn load(path: &camino::Utf8Path) -> Result<Self> {
+        let f = std::fs::File::open(path)
+            .with_context(|| format!("Opening prior build manifest {path}"))?;
+        let manifest: serde_json::Value = serde_json::from_reader(f)
+            .with_context(|| format!("Parsing prior build manifest {path}"))?;
+        let mut layer_of_source = BTreeMap::new();
+        if let Some(layers) = manifest.get("layers").and_then(|v| v.as_array()) {
+            for (idx, layer) in layers.iter().enumerate() {
+                let Some(sources) = layer.get("sources").and_then(|v| v.as_array()) else {
+                    continue;
+                };
+                for source in sources.iter().filter_map(|v| v.as_str()) {
+                    layer_of_source.insert(source.to_string(), idx);
+                }
+            }
+        }
+        Ok(Self { layer_of_source })
+    }
+}
+
+/// Greedily assign sources to at most `max_layers` layers.
+///
+/// Sources are sorted by size descending, breaking ties by source id, so the
+/// same set of inputs always produces the same layering. The largest
+/// sources each get a dedicated layer until `max_layers - 1` are filled; the
+/// final layer absorbs everything else.
+///
+/// Every dedicated slot a pinned source will occupy is reserved up front, so
+/// an unpinned source that happens to sort ahead of a pinned one (e.g. it
+/// grew larger since the prior build) can never steal that slot out from
+/// under it.
+fn assign_layers(
+    mut sources: Vec<ObjectSource>,
+    max_layers: u32,
+    prior: Option<&PriorAssignment>,
+) -> Result<Vec<Vec<ObjectSource>>> {
+    if max_layers == 0 {
+        bail!("--max-layers must be at least 1");
+    }
+    sources.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.id.cmp(&b.id)));
+
+    let max_layers = max_layers as usize;
+    let leftovers_idx = max_layers - 1;
+    let mut layers: Vec<Vec<ObjectSource>> = vec![Vec::new(); max_layers];
+
+    let mut claimed: HashSet<usize> = HashSet::new();
+    if let Some(prior) = prior {
+        claimed.extend(
+            sources
+                .iter()
+                .filter_map(|s| prior.layer_of_source.get(&s.id).copied())
+                .filter(|idx| *idx < leftovers_idx),
+        );
+    }
+
+    let mut next_dedicated = 0usize;
+    for source in sources {
+        let pinned = prior.and_then(|p| p.layer_of_source.get(&source.id).copied());
+        let idx = match pinned {
+            Some(idx) if idx < leftovers_idx => idx,
+            _ => loop {
+                if next_dedicated >= leftovers_idx {
+                    break leftovers_idx;
+                }
+                let candidate = next_dedicated;
+                next_dedicated += 1;
+                if !claimed.contains(&candidate) {
+                    break candidate;
+                }
+            },
+        };
+        layers[idx].push(source);
+    }
+
+    Ok(layers)
+}
+
+/// Parse a single `{id, size, digests}` source entry, the shape a prior
+/// `chunk` run's top-level `sources` array holds.
+fn parse_source_entry(s: &serde_json::Value) -> Result<ObjectSource> {
+    Ok(ObjectSource {
+        id: s
+            .get("id")
+            .and_then(|v| v.as_str())
+            .context("source entry missing id")?
+            .to_string(),
+        size: s
+            .get("size")
+            .and_then(|v| v.as_u64())
+            .context("source entry missing size")?,
+        digests: s
+            .get("digests")
+            .and_then(|v| v.as_array())
+            .map(|a| a.iter().filter_map(|d| d.as_str().map(str::to_string)).collect())
+            .unwrap_or_default(),
+    })
+}
+
+/// Derive a source per existing layer from tag metadata holding
+/// `"layers"` of `{digest, objects: [{digest, size}]}`, for tags that have
+/// never been through `chunk` and so have no precise `sources` record yet.
+fn derive_sources_from_layers(meta: &serde_json::Value, image: &str) -> Result<Vec<ObjectSource>> {
+    let layers = meta
+        .get("layers")
+        .and_then(|v| v.as_array())
+        .with_context(|| format!("Tag {image} has no layer or source metadata to chunk by"))?;
+
+    layers
+        .iter()
+        .enumerate()
+        .map(|(idx, layer)| {
+            let id = layer
+                .get("digest")
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("layer-{idx}"));
+            let objects = layer.get("objects").and_then(|v| v.as_array());
+            let digests: Vec<String> = objects
+                .map(|objs| {
+                    objs.iter()
+                        .filter_map(|o| o.get("digest").and_then(|v| v.as_str()).map(str::to_string))
+                        .collect()
+                })
+                .unwrap_or_default();
+            let size = objects
+                .map(|objs| {
+                    objs.iter()
+                        .filter_map(|o| o.get("size").and_then(|v| v.as_u64()))
+                        .sum()
+                })
+                .unwrap_or(0);
+            Ok(ObjectSource { id, size, digests })
+        })
+        .collect()
+}
+
+/// Determine the sources to chunk by: prefer a prior `chunk` run's precise
+/// top-level `sources` record, falling back to deriving one source per
+/// existing layer from per-layer object listings.
+fn parse_sources(meta: &serde_json::Value, image: &str) -> Result<Vec<ObjectSource>> {
+    if let Some(sources) = meta.get("sources").and_then(|v| v.as_array()) {
+        return sources.iter().map(parse_source_entry).collect();
+    }
+    derive_sources_from_layers(meta, image)
+}
+
+/// Build a layer's blob by concatenating its member objects in a
+/// deterministic order, and name it by the sha256 of that concatenation.
+fn build_layer_blob(repo: &crate::repo::Repo, layer: &[ObjectSource]) -> Result<(String, Vec<u8>)> {
+    let mut blob = Vec::new();
+    for source in layer {
+        for digest in &source.digests {
+            let bytes = repo
+                .read_object(digest)
+                .with_context(|| format!("Reading object {digest} for source {}", source.id))?;
+            blob.extend_from_slice(&bytes);
+        }
+    }
+    let digest = format!("sha256:{:x}", Sha256::digest(&blob));
+    Ok((digest, blob))
+}
+
+/// Repack a stored image's objects into at most `--max-layers` cache-friendly
+/// layers, writing each layer's blob and the resulting [`ImageManifest`] back
+/// into the repo under the same tag.
+pub(crate) async fn cli_chunk(opts: ExportOpts) -> Result<()> {
+    let repo = opts.repo_opts.open()?;
+    let meta = repo
+        .read_artifact_metadata(&opts.image)?
+        .with_context(|| format!("No such tag: {}", opts.image))?;
+
+    let sources = parse_sources(&meta, &opts.image)?;
+    let prior = opts
+        .prior_build
+        .as_deref()
+        .map(PriorAssignment::load)
+        .transpose()?;
+
+    let layers = assign_layers(sources, opts.max_layers, prior.as_ref())?;
+
+    let mut manifest_layers = Vec::with_capacity(layers.len());
+    let mut manifest_sources = Vec::new();
+    for (idx, layer) in layers.iter().enumerate() {
+        let (digest, blob) = build_layer_blob(&repo, layer)?;
+        repo.write_object(&digest, &blob)?;
+
+        let size = blob.len() as u64;
+        let objects: usize = layer.iter().map(|s| s.digests.len()).sum();
+        println!(
+            "layer {idx}: {} source(s), {objects} object(s), {size} bytes -> {digest}",
+            layer.len()
+        );
+
+        manifest_layers.push(ManifestLayer {
+            sources: layer.iter().map(|s| s.id.clone()).collect(),
+            digest,
+            size,
+            objects: layer
+                .iter()
+                .flat_map(|s| s.digests.iter().cloned())
+                .collect(),
+        });
+        manifest_sources.extend(layer.iter().map(|s| SourceRecord {
+            id: s.id.clone(),
+            size: s.size,
+            digests: s.digests.clone(),
+        }));
+    }
+
+    let manifest = ImageManifest {
+        layers: manifest_layers,
+        sources: manifest_sources,
+    };
+    repo.write_artifact_metadata(&opts.image, &serde_json::to_value(&manifest)?)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(id: &str, size: u64) -> ObjectSource {
+        ObjectSource {
+            id: id.to_string(),
+            size,
+            digests: Vec::new(),
+        }
+    }
+
+    fn ids(layers: &[Vec<ObjectSource>]) -> Vec<Vec<&str>> {
+        layers
+            .iter()
+            .map(|l| l.iter().map(|s| s.id.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn sorts_largest_first_and_breaks_ties_by_id() {
+        let sources = vec![source("b", 10), source("a", 10), source("c", 20)];
+        let layers = assign_layers(sources, 4, None).unwrap();
+        assert_eq!(ids(&layers), vec![vec!["c"], vec!["a"], vec!["b"], vec![]]);
+    }
+
+    #[test]
+    fn overflow_goes_to_leftovers_layer() {
+        let sources = vec![source("a", 30), source("b", 20), source("c", 10)];
+        let layers = assign_layers(sources, 2, None).unwrap();
+        assert_eq!(ids(&layers), vec![vec!["a"], vec!["b", "c"]]);
+    }
+
+    #[test]
+    fn zero_max_layers_is_rejected() {
+        assert!(assign_layers(vec![source("a", 1)], 0, None).is_err());
+    }
+
+    #[test]
+    fn pinned_source_keeps_its_prior_slot_even_when_outsorted() {
+        // "a" was pinned to layer 0 in a prior build. This build, a new
+        // source "c" sorts ahead of it (it's now the largest), which must
+        // not bump "a" out of its pinned slot.
+        let mut layer_of_source = BTreeMap::new();
+        layer_of_source.insert("a".to_string(), 0);
+        let prior = PriorAssignment { layer_of_source };
+
+        let sources = vec![source("c", 100), source("a", 50), source("b", 10)];
+        let layers = assign_layers(sources, 3, Some(&prior)).unwrap();
+
+        assert_eq!(ids(&layers), vec![vec!["a"], vec!["c"], vec!["b"]]);
+    }
+
+    #[test]
+    fn prior_pin_into_leftovers_slot_is_ignored() {
+        // A pin into what is now the leftovers index doesn't reserve
+        // anything; the source is just re-assigned normally.
+        let mut layer_of_source = BTreeMap::new();
+        layer_of_source.insert("a".to_string(), 1);
+        let prior = PriorAssignment { layer_of_source };
+
+        let sources = vec![source("a", 10)];
+        let layers = assign_layers(sources, 2, Some(&prior)).unwrap();
+
+        assert_eq!(ids(&layers), vec![vec!["a"], vec![]]);
+    }
+}
@@ -8,8 +8,11 @@ use clap::Parser;
 use ocidir::cap_std;
 use pull::cli_pull;
 
+mod chunking;
 mod fileutils;
 pub mod pull;
+mod pull_plan;
+mod remote;
 pub mod repo;
 mod sha256descriptor;
 mod unpack;
@@ -20,6 +23,13 @@ pub(crate) struct RepoOpts {
     /// Path to the repository
     #[clap(long, value_parser)]
     repo: Utf8PathBuf,
+
+    /// URL of a remote object store (e.g. `s3://bucket/prefix`) used as a
+    /// shared, content-addressed cache: objects are checked and fetched from
+    /// here during `pull`, and newly-created objects are uploaded back after
+    /// a successful import.
+    #[clap(long = "remote", value_parser)]
+    remote_url: Option<url::Url>,
 }
 
 impl RepoOpts {
@@ -29,6 +39,11 @@ impl RepoOpts {
             .with_context(|| format!("Opening {repo}"))?;
         crate::repo::Repo::open(d)
     }
+
+    /// Open the configured remote object store, if any.
+    pub(crate) async fn remote(&self) -> Result<Option<Box<dyn crate::remote::RemoteStore>>> {
+        crate::remote::open(self.remote_url.as_ref()).await
+    }
 }
 
 /// Options for importing container.
@@ -39,6 +54,11 @@ pub(crate) struct PullOpts {
 
     /// Image reference
     image: String,
+
+    /// Report how many objects/bytes would actually be fetched versus
+    /// reused from already-stored objects, without writing anything
+    #[clap(long)]
+    dry_run: bool,
 }
 
 /// Options for importing container.
@@ -51,6 +71,24 @@ pub(crate) struct UnpackOpts {
     image: String,
 }
 
+/// Options for repacking a stored image into cache-friendly layers.
+#[derive(Debug, Parser)]
+pub(crate) struct ExportOpts {
+    #[clap(flatten)]
+    repo_opts: RepoOpts,
+
+    /// Image reference (tag) to repack
+    image: String,
+
+    /// Maximum number of layers to produce
+    #[clap(long)]
+    max_layers: u32,
+
+    /// Manifest from a prior build; sources keep their previous layer index where possible
+    #[clap(long, value_parser)]
+    prior_build: Option<Utf8PathBuf>,
+}
+
 /// Options for creating a repo
 #[derive(Debug, Parser)]
 pub(crate) struct CreateOpts {
@@ -62,6 +100,199 @@ pub(crate) struct CreateOpts {
     require_verity: bool,
 }
 
+/// Output format for commands that emit machine-readable data.
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+#[clap(rename_all = "kebab-case")]
+pub(crate) enum OutputFormat {
+    Json,
+    Yaml,
+    Table,
+}
+
+/// Shared `--output` flag for [`Opt::List`] and [`Opt::Inspect`].
+#[derive(Debug, Parser)]
+pub(crate) struct OutputOpts {
+    /// Output format
+    #[clap(long, value_enum, default_value_t = OutputFormat::Table)]
+    output: OutputFormat,
+}
+
+/// Options for listing tags
+#[derive(Debug, Parser)]
+pub(crate) struct ListOpts {
+    #[clap(flatten)]
+    repo_opts: RepoOpts,
+
+    #[clap(flatten)]
+    output: OutputOpts,
+
+    /// Only list tags matching this glob/prefix filter
+    filter: Option<String>,
+}
+
+/// Options for inspecting a tag
+#[derive(Debug, Parser)]
+pub(crate) struct InspectOpts {
+    #[clap(flatten)]
+    repo_opts: RepoOpts,
+
+    #[clap(flatten)]
+    output: OutputOpts,
+
+    /// Query this tag
+    name: String,
+}
+
+/// Compute per-layer digests and object-sharing stats from an image's stored
+/// metadata, so `Inspect` output is self-contained and scriptable without a
+/// second round-trip into the repo. Objects may be `{digest, size}` maps or
+/// bare digest strings (as `chunk` writes them); the latter count as 0 bytes.
+fn augment_inspect_metadata(meta: &mut serde_json::Value) {
+    let layers = meta
+        .get("layers")
+        .and_then(|v| v.as_array())
+        .cloned()
+        .unwrap_or_default();
+
+    let mut layer_digests = Vec::new();
+    let mut total_objects = 0u64;
+    let mut total_bytes = 0u64;
+
+    for layer in &layers {
+        let Some(objects) = layer.get("objects").and_then(|v| v.as_array()) else {
+            layer_digests.push(Vec::new());
+            continue;
+        };
+        let mut digests = Vec::new();
+        for obj in objects {
+            let digest = obj
+                .get("digest")
+                .and_then(|v| v.as_str())
+                .or_else(|| obj.as_str())
+                .unwrap_or_default()
+                .to_string();
+            let size = obj.get("size").and_then(|v| v.as_u64()).unwrap_or_default();
+            total_objects += 1;
+            total_bytes += size;
+            digests.push(digest);
+        }
+        layer_digests.push(digests);
+    }
+
+    // Size of each distinct digest, counted once regardless of how many
+    // layers reference it.
+    let mut seen_digests: std::collections::BTreeSet<String> = Default::default();
+    let unique_bytes: u64 = layers
+        .iter()
+        .flat_map(|l| {
+            l.get("objects")
+                .and_then(|v| v.as_array())
+                .cloned()
+                .unwrap_or_default()
+        })
+        .filter(|o| {
+            let digest = o.get("digest").and_then(|v| v.as_str()).unwrap_or_default();
+            seen_digests.insert(digest.to_string())
+        })
+        .filter_map(|o| o.get("size").and_then(|v| v.as_u64()))
+        .sum();
+    let shared_bytes = total_bytes - unique_bytes;
+
+    if let Some(obj) = meta.as_object_mut() {
+        obj.insert("total_object_count".into(), total_objects.into());
+        obj.insert("unique_object_bytes".into(), unique_bytes.into());
+        obj.insert("shared_object_bytes".into(), shared_bytes.into());
+        obj.insert(
+            "layer_digests".into(),
+            serde_json::Value::Array(
+                layer_digests
+                    .into_iter()
+                    .map(|d| serde_json::Value::Array(d.into_iter().map(Into::into).collect()))
+                    .collect(),
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(objects: &[(&str, u64)]) -> serde_json::Value {
+        serde_json::json!({
+            "objects": objects
+                .iter()
+                .map(|(digest, size)| serde_json::json!({"digest": digest, "size": size}))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    #[test]
+    fn shared_object_counted_once_in_unique_bytes() {
+        // A 100-byte object appearing in two layers is 100 unique bytes and
+        // 100 duplicate bytes, not 0 unique / 200 shared.
+        let mut meta = serde_json::json!({
+            "layers": [layer(&[("sha256:a", 100)]), layer(&[("sha256:a", 100)])],
+        });
+
+        augment_inspect_metadata(&mut meta);
+
+        assert_eq!(meta["total_object_count"], 2);
+        assert_eq!(meta["unique_object_bytes"], 100);
+        assert_eq!(meta["shared_object_bytes"], 100);
+    }
+
+    #[test]
+    fn all_distinct_objects_have_no_shared_bytes() {
+        let mut meta = serde_json::json!({
+            "layers": [layer(&[("sha256:a", 10), ("sha256:b", 20)])],
+        });
+
+        augment_inspect_metadata(&mut meta);
+
+        assert_eq!(meta["total_object_count"], 2);
+        assert_eq!(meta["unique_object_bytes"], 30);
+        assert_eq!(meta["shared_object_bytes"], 0);
+    }
+
+    #[test]
+    fn missing_layers_key_yields_zeroed_stats() {
+        let mut meta = serde_json::json!({});
+
+        augment_inspect_metadata(&mut meta);
+
+        assert_eq!(meta["total_object_count"], 0);
+        assert_eq!(meta["unique_object_bytes"], 0);
+        assert_eq!(meta["shared_object_bytes"], 0);
+    }
+}
+
+/// Render a JSON value as a simple key/value table for terminal use.
+fn print_value_table(value: &serde_json::Value) {
+    match value.as_object() {
+        Some(obj) => {
+            for (key, value) in obj {
+                match value {
+                    serde_json::Value::Array(_) | serde_json::Value::Object(_) => {
+                        println!("{key}:\n  {value}")
+                    }
+                    _ => println!("{key}: {value}"),
+                }
+            }
+        }
+        None => println!("{value}"),
+    }
+}
+
+fn print_value(value: &serde_json::Value, format: OutputFormat) -> Result<()> {
+    match format {
+        OutputFormat::Json => serde_json::to_writer_pretty(std::io::stdout(), value)?,
+        OutputFormat::Yaml => serde_yaml::to_writer(std::io::stdout(), value)?,
+        OutputFormat::Table => print_value_table(value),
+    }
+    Ok(())
+}
+
 /// Toplevel options
 #[derive(Debug, Parser)]
 #[clap(name = "composefs")]
@@ -71,18 +302,14 @@ pub(crate) enum Opt {
     /// Initialize a repo
     Create(CreateOpts),
     /// List all images
-    List(RepoOpts),
+    List(ListOpts),
     /// Query a tag
-    Inspect {
-        #[clap(flatten)]
-        repo_opts: RepoOpts,
-
-        /// Query this tag
-        name: String,
-    },
+    Inspect(InspectOpts),
     /// Pull an image
     Pull(PullOpts),
     Unpack(UnpackOpts),
+    /// Repack a stored image's objects into at most `--max-layers` cache-friendly layers
+    Chunk(ExportOpts),
 }
 
 /// Parse the provided arguments and execute.
@@ -107,21 +334,29 @@ async fn run_from_opt(opt: Opt) -> Result<()> {
             Ok(())
         }
         Opt::List(opts) => {
-            let repo = opts.open()?;
-            for tag in repo.list_tags(None).await? {
-                println!("{tag}");
+            let repo = opts.repo_opts.open()?;
+            let tags = repo.list_tags(opts.filter.as_deref()).await?;
+            match opts.output.output {
+                OutputFormat::Table => {
+                    for tag in &tags {
+                        println!("{tag}");
+                    }
+                    Ok(())
+                }
+                format => print_value(&serde_json::to_value(&tags)?, format),
             }
-            Ok(())
         }
-        Opt::Inspect { repo_opts, name } => {
-            let repo = repo_opts.open()?;
-            if let Some(meta) = repo.read_artifact_metadata(&name)? {
-                let mut stdout = std::io::stdout().lock();
-                serde_json::to_writer(&mut stdout, &meta)?;
-            }
-            Ok(())
+        Opt::Inspect(opts) => {
+            let repo = opts.repo_opts.open()?;
+            let Some(meta) = repo.read_artifact_metadata(&opts.name)? else {
+                return Ok(());
+            };
+            let mut meta = serde_json::to_value(&meta)?;
+            augment_inspect_metadata(&mut meta);
+            print_value(&meta, opts.output.output)
         }
         Opt::Pull(opts) => cli_pull(opts).await,
         Opt::Unpack(opts) => unpack::cli_unpack(opts).await,
+        Opt::Chunk(opts) => chunking::cli_chunk(opts).await,
     }
 }
@@ -0,0 +1,89 @@
+//! Pull an OCI image into the repo.
+//!
+//! Layers are content-addressed by their OCI descriptor digest, so a layer
+//! already stored locally (from a previous pull, of this or another image)
+//! is reused instead of re-fetched. When a remote object store is
+//! configured (`--remote`), it's consulted before falling back to the
+//! registry, and any layer newly fetched from the registry is uploaded back
+//! to it, so the local repo acts as a write-through cache in front of the
+//! remote.
+
+use anyhow::{Context, Result};
+use containers_image_proxy::ImageProxy;
+use ocidir::oci_spec::image::ImageManifest;
+use tokio::io::AsyncReadExt;
+
+use crate::pull_plan::PullPlan;
+use crate::PullOpts;
+
+/// Pull `opts.image` into the configured repo.
+pub async fn cli_pull(opts: PullOpts) -> Result<()> {
+    let repo = opts.repo_opts.open()?;
+    let remote = opts.repo_opts.remote().await?;
+
+    let proxy = ImageProxy::new().await?;
+    let img = proxy
+        .open_image(&opts.image)
+        .await
+        .with_context(|| format!("Opening image {}", opts.image))?;
+    let (_digest, raw_manifest) = proxy.fetch_manifest(&img).await?;
+    let manifest = ImageManifest::from_reader(raw_manifest.as_slice())
+        .context("Parsing image manifest")?;
+
+    let stored = repo.stored_object_digests()?;
+    let mut total = PullPlan::default();
+
+    for layer in manifest.layers() {
+        let digest = layer.digest().to_string();
+        let size = u64::try_from(layer.size()).unwrap_or(0);
+        let plan = PullPlan::compute(&[(digest.clone(), size)], &stored);
+
+        if opts.dry_run {
+            println!("{digest}: {}", plan.summarize());
+            total.missing.extend(plan.missing);
+            total.reused.extend(plan.reused);
+            total.missing_bytes += plan.missing_bytes;
+            total.reused_bytes += plan.reused_bytes;
+            continue;
+        }
+
+        if !plan.missing.is_empty() {
+            let (data, from_remote) = fetch_layer(&proxy, &img, remote.as_deref(), &digest).await?;
+            repo.write_object(&digest, &data)?;
+            if let Some(remote) = remote.as_ref().filter(|_| !from_remote) {
+                remote.put(&digest, &data).await?;
+            }
+        }
+    }
+
+    if opts.dry_run {
+        println!("total: {}", total.summarize());
+    }
+
+    proxy.close_image(&img).await?;
+    Ok(())
+}
+
+/// Fetch a layer's bytes, preferring the remote object store (if configured
+/// and it already has the object) over the registry. The returned flag is
+/// true when the bytes came from the remote.
+async fn fetch_layer(
+    proxy: &ImageProxy,
+    img: &containers_image_proxy::OpenedImage,
+    remote: Option<&dyn crate::remote::RemoteStore>,
+    digest: &str,
+) -> Result<(Vec<u8>, bool)> {
+    if let Some(remote) = remote {
+        if remote.contains(digest).await? {
+            return Ok((remote.get(digest).await?, true));
+        }
+    }
+    let (mut blob, driver) = proxy
+        .get_blob(img, digest, 0)
+        .await
+        .with_context(|| format!("Fetching layer {digest}"))?;
+    let mut data = Vec::new();
+    blob.read_to_end(&mut data).await?;
+    driver.await??;
+    Ok((data, false))
+}
@@ -0,0 +1,111 @@
+//! Planning for deduplicating, resumable pulls.
+//!
+//! Because composefs objects are content-addressed, two images that share
+//! base layers share on-disk objects by digest. Given the set of digests a
+//! repo already has (from a previous image, or from an interrupted pull
+//! that got partway through this one), [`PullPlan::compute`] determines
+//! which of a layer's objects still need to be fetched and which can be
+//! reused as-is, so the fetch path only ever streams what's missing and an
+//! interrupted pull can resume from its checkpoint rather than restarting.
+
+use std::collections::BTreeSet;
+
+/// What a pull would do for a single layer: which digests are missing and
+/// need to be fetched, and which are already present and can be reused.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct PullPlan {
+    pub(crate) missing: Vec<String>,
+    pub(crate) reused: Vec<String>,
+    pub(crate) reused_bytes: u64,
+    pub(crate) missing_bytes: u64,
+}
+
+impl PullPlan {
+    /// Compute the plan for a layer's objects given the digests already
+    /// stored in the repo (from prior images and/or a checkpointed,
+    /// previously-interrupted pull of this same image).
+    pub(crate) fn compute(layer_objects: &[(String, u64)], already_stored: &BTreeSet<String>) -> Self {
+        let mut plan = Self::default();
+        for (digest, size) in layer_objects {
+            if already_stored.contains(digest) {
+                plan.reused.push(digest.clone());
+                plan.reused_bytes += size;
+            } else {
+                plan.missing.push(digest.clone());
+                plan.missing_bytes += size;
+            }
+        }
+        plan
+    }
+
+    /// Render a one-line human summary, used for `--dry-run` output.
+    pub(crate) fn summarize(&self) -> String {
+        format!(
+            "{} object(s) to fetch ({} bytes), {} object(s) reused ({} bytes)",
+            self.missing.len(),
+            self.missing_bytes,
+            self.reused.len(),
+            self.reused_bytes,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_missing_and_reused_by_digest() {
+        let stored = BTreeSet::from(["sha256:a".to_string(), "sha256:c".to_string()]);
+        let layer_objects = vec![
+            ("sha256:a".to_string(), 10),
+            ("sha256:b".to_string(), 20),
+            ("sha256:c".to_string(), 30),
+        ];
+
+        let plan = PullPlan::compute(&layer_objects, &stored);
+
+        assert_eq!(plan.missing, vec!["sha256:b".to_string()]);
+        assert_eq!(plan.missing_bytes, 20);
+        assert_eq!(plan.reused, vec!["sha256:a".to_string(), "sha256:c".to_string()]);
+        assert_eq!(plan.reused_bytes, 40);
+    }
+
+    #[test]
+    fn empty_stored_set_means_everything_is_missing() {
+        let layer_objects = vec![("sha256:a".to_string(), 5), ("sha256:b".to_string(), 7)];
+
+        let plan = PullPlan::compute(&layer_objects, &BTreeSet::new());
+
+        assert_eq!(plan.missing.len(), 2);
+        assert_eq!(plan.missing_bytes, 12);
+        assert!(plan.reused.is_empty());
+        assert_eq!(plan.reused_bytes, 0);
+    }
+
+    #[test]
+    fn fully_stored_layer_is_entirely_reused() {
+        let stored = BTreeSet::from(["sha256:a".to_string()]);
+        let layer_objects = vec![("sha256:a".to_string(), 5)];
+
+        let plan = PullPlan::compute(&layer_objects, &stored);
+
+        assert!(plan.missing.is_empty());
+        assert_eq!(plan.missing_bytes, 0);
+        assert_eq!(plan.reused, vec!["sha256:a".to_string()]);
+        assert_eq!(plan.reused_bytes, 5);
+    }
+
+    #[test]
+    fn summarize_reports_counts_and_bytes() {
+        let stored = BTreeSet::from(["sha256:a".to_string()]);
+        let layer_objects = vec![("sha256:a".to_string(), 5), ("sha256:b".to_string(), 7)];
+
+        let plan = PullPlan::compute(&layer_objects, &stored);
+
+        assert_eq!(
+            plan.summarize(),
+            "1 object(s) to fetch (7 bytes), 1 object(s) reused (5 bytes)"
+        );
+    }
+}
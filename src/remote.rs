@@ -0,0 +1,122 @@
+//! A pluggable remote object store used as a shared cache for
+//! content-addressed objects, keyed by their digest (sha256 descriptor /
+//! fsverity digest).
+//!
+//! This lets multiple machines share a single pool of already-split,
+//! already-verified objects: before importing a layer the local repo probes
+//! the remote for objects it's missing, fetches only those, and uploads
+//! back whatever it newly created, acting as a write-through cache in front
+//! of the remote.
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+
+/// A remote, content-addressed object store.
+///
+/// [`RemoteStore::contains`] is a cheap existence probe (e.g. an HTTP HEAD)
+/// that callers should always check before [`RemoteStore::get`], so a GET is
+/// only issued for objects the remote actually holds.
+#[async_trait]
+pub(crate) trait RemoteStore: Send + Sync {
+    /// Return whether an object with this digest exists remotely, without
+    /// transferring its contents.
+    async fn contains(&self, digest: &str) -> Result<bool>;
+
+    /// Fetch the bytes of an object by digest. Callers should have already
+    /// checked [`RemoteStore::contains`].
+    async fn get(&self, digest: &str) -> Result<Vec<u8>>;
+
+    /// Upload an object's bytes, keyed by its digest.
+    async fn put(&self, digest: &str, data: &[u8]) -> Result<()>;
+}
+
+/// An S3-compatible [`RemoteStore`], addressing objects as `<prefix>/<digest>`.
+pub(crate) struct S3RemoteStore {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+    prefix: String,
+}
+
+impl S3RemoteStore {
+    /// Connect to an S3-compatible endpoint using the ambient AWS credential
+    /// chain (environment, profile, or instance metadata).
+    pub(crate) async fn new(url: &url::Url) -> Result<Self> {
+        let bucket = url
+            .host_str()
+            .with_context(|| format!("Remote URL missing bucket host: {url}"))?
+            .to_string();
+        let prefix = url.path().trim_start_matches('/').to_string();
+        let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+        let client = aws_sdk_s3::Client::new(&config);
+        Ok(Self {
+            client,
+            bucket,
+            prefix,
+        })
+    }
+
+    fn key(&self, digest: &str) -> String {
+        if self.prefix.is_empty() {
+            digest.to_string()
+        } else {
+            format!("{}/{digest}", self.prefix)
+        }
+    }
+}
+
+#[async_trait]
+impl RemoteStore for S3RemoteStore {
+    async fn contains(&self, digest: &str) -> Result<bool> {
+        match self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(self.key(digest))
+            .send()
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get(&self, digest: &str) -> Result<Vec<u8>> {
+        let obj = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.key(digest))
+            .send()
+            .await
+            .with_context(|| format!("Fetching object {digest}"))?;
+        Ok(obj.body.collect().await?.into_bytes().to_vec())
+    }
+
+    async fn put(&self, digest: &str, data: &[u8]) -> Result<()> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(self.key(digest))
+            .body(data.to_vec().into())
+            .send()
+            .await
+            .with_context(|| format!("Uploading object {digest}"))?;
+        Ok(())
+    }
+}
+
+/// Construct the configured remote store, if any.
+///
+/// Wiring the result into `pull`'s fetch/import path (check remote before
+/// fetching, upload newly-written objects after import) lives in the `pull`
+/// module.
+pub(crate) async fn open(url: Option<&url::Url>) -> Result<Option<Box<dyn RemoteStore>>> {
+    let Some(url) = url else {
+        return Ok(None);
+    };
+    match url.scheme() {
+        "s3" => Ok(Some(Box::new(S3RemoteStore::new(url).await?))),
+        other => bail!("Unsupported remote store scheme: {other}"),
+    }
+}
@@ -0,0 +1,136 @@
+//! The on-disk, content-addressed object repository.
+//!
+//! Objects (layer blobs, and the individual objects they're split into) are
+//! stored under `objects/`, keyed by their digest. Tags live under `tags/`
+//! and hold a JSON blob of metadata for the artifact they point at (the
+//! shape is command-specific: `chunk` records the resulting
+//! [`crate::chunking`] layering; `pull` stores layer blobs under `objects/`
+//! but writes no tag).
+
+use std::collections::BTreeSet;
+
+use anyhow::{bail, Context, Result};
+use cap_std::fs::Dir;
+use cap_std_ext::cap_std;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use sha2::{Digest, Sha256};
+
+const OBJECTS_DIR: &str = "objects";
+const TAGS_DIR: &str = "tags";
+
+/// A composefs object repository rooted at a directory.
+#[derive(Debug)]
+pub struct Repo {
+    dir: Dir,
+}
+
+impl Repo {
+    /// Initialize a new, empty repository in `dir`.
+    pub fn init(dir: &Dir, require_verity: bool) -> Result<Self> {
+        dir.create_dir_all(OBJECTS_DIR)
+            .context("Creating objects dir")?;
+        dir.create_dir_all(TAGS_DIR).context("Creating tags dir")?;
+        // `require_verity` is neither recorded nor enforced: objects are
+        // written without fsverity checks.
+        let _ = require_verity;
+        Self::open(dir.try_clone()?)
+    }
+
+    /// Open an existing repository.
+    pub fn open(dir: Dir) -> Result<Self> {
+        Ok(Self { dir })
+    }
+
+    /// List all tags, optionally filtered by a glob/prefix pattern (a
+    /// trailing `*` matches any suffix; otherwise the filter must match the
+    /// full tag name).
+    pub async fn list_tags(&self, filter: Option<&str>) -> Result<Vec<String>> {
+        let mut tags = Vec::new();
+        for entry in self.dir.read_dir(TAGS_DIR)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if filter.is_some_and(|pat| !tag_matches(pat, &name)) {
+                continue;
+            }
+            tags.push(name);
+        }
+        tags.sort();
+        Ok(tags)
+    }
+
+    /// Read a tag's stored metadata, if present.
+    pub fn read_artifact_metadata(&self, name: &str) -> Result<Option<serde_json::Value>> {
+        let path = format!("{TAGS_DIR}/{name}");
+        self.dir
+            .open_optional(&path)
+            .with_context(|| format!("Opening tag {name}"))?
+            .map(|f| serde_json::from_reader(f).with_context(|| format!("Parsing tag {name}")))
+            .transpose()
+    }
+
+    /// Persist a tag's metadata, e.g. the [`crate::chunking::ImageManifest`]
+    /// produced by repacking an image.
+    pub fn write_artifact_metadata(&self, name: &str, meta: &serde_json::Value) -> Result<()> {
+        let path = format!("{TAGS_DIR}/{name}");
+        self.dir
+            .atomic_replace_with(&path, |w| serde_json::to_writer(w, meta))
+            .with_context(|| format!("Writing tag {name}"))?;
+        Ok(())
+    }
+
+    /// Return the digests of every object already stored in the repo.
+    pub fn stored_object_digests(&self) -> Result<BTreeSet<String>> {
+        let mut digests = BTreeSet::new();
+        for entry in self.dir.read_dir(OBJECTS_DIR)? {
+            digests.insert(entry?.file_name().to_string_lossy().into_owned());
+        }
+        Ok(digests)
+    }
+
+    /// Return whether an object is already stored locally.
+    pub fn has_object(&self, digest: &str) -> Result<bool> {
+        Ok(self
+            .dir
+            .try_exists(format!("{OBJECTS_DIR}/{digest}"))?)
+    }
+
+    /// Read an object's bytes by digest.
+    pub fn read_object(&self, digest: &str) -> Result<Vec<u8>> {
+        self.dir
+            .read_to_vec(format!("{OBJECTS_DIR}/{digest}"))
+            .with_context(|| format!("Reading object {digest}"))
+    }
+
+    /// Write an object's bytes, keyed by digest. A no-op if the object is
+    /// already present, since content-addressing means it's byte-identical.
+    ///
+    /// The digest is verified against the bytes before anything is written,
+    /// so a stale or corrupted object handed in from a remote store or
+    /// registry can never be accepted under someone else's digest.
+    pub fn write_object(&self, digest: &str, data: &[u8]) -> Result<()> {
+        verify_digest(digest, data)?;
+        if self.has_object(digest)? {
+            return Ok(());
+        }
+        self.dir
+            .atomic_write(format!("{OBJECTS_DIR}/{digest}"), data)
+            .with_context(|| format!("Writing object {digest}"))
+    }
+}
+
+fn verify_digest(digest: &str, data: &[u8]) -> Result<()> {
+    let Some(expected_hex) = digest.strip_prefix("sha256:") else {
+        bail!("Unsupported digest algorithm: {digest}");
+    };
+    let actual_hex = format!("{:x}", Sha256::digest(data));
+    if actual_hex != expected_hex {
+        bail!("Digest mismatch: expected {digest}, got sha256:{actual_hex}");
+    }
+    Ok(())
+}
+
+fn tag_matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == pattern,
+    }
+}